@@ -1,36 +1,126 @@
-use opengl_graphics::{GlGraphics, OpenGL};
+use opengl_graphics::{GlGraphics, GlyphCache, OpenGL, TextureSettings};
 
 use glutin_window::GlutinWindow as Window;
-use graphics::Transformed;
+use graphics::{Text, Transformed};
 use piston::event_loop::{EventSettings, Events};
-use piston::input::{Button, ButtonEvent, Key, RenderArgs, RenderEvent, UpdateEvent};
+use piston::input::{Button, ButtonEvent, Key, RenderArgs, RenderEvent, UpdateArgs, UpdateEvent};
 use piston::window::WindowSettings;
 
 use rand::Rng;
-use std::collections::LinkedList;
+use serde::Deserialize;
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, HashSet, LinkedList};
+use std::env;
+use std::fs;
+use std::process;
 
 const WIDTH: u8 = 30;
 const HEIGHT: u8 = 20;
 const GRID_STEP: f64 = 25.0;
+const DEFAULT_SPEED: f64 = 8.0;
+const MAX_SPEED: f64 = 20.0;
+const FOOD_PER_SPEED_BUMP: u32 = 5;
+const DEFAULT_BACKGROUND_COLOR: u32 = 0xFFFCEDCC;
+const DEFAULT_SNAKE_COLOR: u32 = 0xFFFC2908;
+const DEFAULT_FOOD_COLOR: u32 = 0xFF146687;
+
+#[derive(Deserialize)]
+#[serde(default)]
+struct Config {
+    width: u8,
+    height: u8,
+    cell_size: f64,
+    background_color: u32,
+    snake_color: u32,
+    food_color: u32,
+    speed: f64,
+    walls: Vec<(u8, u8)>,
+    wrap: bool,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            width: WIDTH,
+            height: HEIGHT,
+            cell_size: GRID_STEP,
+            background_color: DEFAULT_BACKGROUND_COLOR,
+            snake_color: DEFAULT_SNAKE_COLOR,
+            food_color: DEFAULT_FOOD_COLOR,
+            speed: DEFAULT_SPEED,
+            walls: Vec::new(),
+            wrap: false,
+        }
+    }
+}
+
+impl Config {
+    fn load(path: &str) -> Config {
+        let contents = fs::read_to_string(path).unwrap_or_else(|error| {
+            eprintln!("Could not read the config file {}: {}", path, error);
+            process::exit(1);
+        });
+        json5::from_str(&contents).unwrap_or_else(|error| {
+            eprintln!("Could not parse the config file {}: {}", path, error);
+            process::exit(1);
+        })
+    }
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum GameState {
+    Running,
+    GameOver,
+    Won,
+}
 
 pub struct Game {
     gl: GlGraphics,
+    glyphs: Option<GlyphCache<'static>>,
     size: (u8, u8),
+    cell_size: f64,
+    background_color: [f32; 4],
+    snake_color: [f32; 4],
+    food_color: [f32; 4],
+    walls: HashSet<(i32, i32)>,
     snake: Snake,
     food: Food,
     pending_direction: Option<Direction>,
     score: u32,
+    state: GameState,
+    speed: f64,
+    accumulator: f64,
+    autopilot: bool,
+    wrap: bool,
 }
 
 impl Game {
-    fn new(gl: GlGraphics, size: (u8, u8)) -> Self {
+    fn new(gl: GlGraphics, glyphs: Option<GlyphCache<'static>>, config: &Config) -> Self {
+        let walls = config
+            .walls
+            .iter()
+            .map(|&(x, y)| (x as i32, y as i32))
+            .filter(|&(x, y)| x < config.width as i32 && y < config.height as i32)
+            .collect();
+
         let mut game = Game {
             gl,
-            size,
+            glyphs,
+            size: (config.width, config.height),
+            cell_size: config.cell_size,
+            background_color: Color(config.background_color).into(),
+            snake_color: Color(config.snake_color).into(),
+            food_color: Color(config.food_color).into(),
+            walls,
             snake: Snake::new(),
             food: Food { position: (0, 0) },
             pending_direction: None,
             score: 0,
+            state: GameState::Running,
+            speed: config.speed,
+            accumulator: 0.0,
+            wrap: config.wrap,
+            autopilot: false,
         };
         game.generate_food();
 
@@ -38,17 +128,102 @@ impl Game {
     }
 
     fn render(&mut self, args: &RenderArgs) {
-        let background_color: [f32; 4] = [0.99, 0.93, 0.8, 1.0];
+        let background_color = self.background_color;
 
         self.gl.draw(args.viewport(), |_c, gl| {
             graphics::clear(background_color, gl);
         });
-        self.food.render(&mut self.gl, args);
-        self.snake.render(&mut self.gl, args);
+        self.render_walls(args);
+        self.food
+            .render(&mut self.gl, args, self.cell_size, self.food_color);
+        self.snake
+            .render(&mut self.gl, args, self.cell_size, self.snake_color);
+
+        match self.state {
+            GameState::GameOver => self.render_end_overlay(args, "Game over!"),
+            GameState::Won => self.render_end_overlay(args, "You win!"),
+            GameState::Running => {}
+        }
+    }
+
+    fn render_walls(&mut self, args: &RenderArgs) {
+        let wall_color: [f32; 4] = [0.3, 0.3, 0.3, 1.0];
+        let cell_size = self.cell_size;
+        let walls = &self.walls;
+
+        self.gl.draw(args.viewport(), |c, gl| {
+            for &(x, y) in walls {
+                let square = graphics::rectangle::square(
+                    x as f64 * cell_size + 0.5,
+                    y as f64 * cell_size + 0.5,
+                    cell_size - 1.0,
+                );
+                graphics::rectangle(wall_color, square, c.transform, gl);
+            }
+        });
     }
 
-    fn update(&mut self) -> bool {
-        self.snake.update(&self.food, self.pending_direction);
+    fn render_end_overlay(&mut self, args: &RenderArgs, headline: &str) {
+        let overlay_color: [f32; 4] = [0.04, 0.05, 0.06, 0.6];
+        let text_color: [f32; 4] = [0.99, 0.93, 0.8, 1.0];
+        let score_text = format!("{} Score: {}", headline, self.score);
+        let prompt_text = "Press Enter to play again";
+        let glyphs = self.glyphs.as_mut();
+
+        self.gl.draw(args.viewport(), |c, gl| {
+            let overlay = graphics::rectangle::rectangle_by_corners(
+                0.0,
+                0.0,
+                args.window_size[0],
+                args.window_size[1],
+            );
+            graphics::rectangle(overlay_color, overlay, c.transform, gl);
+
+            if let Some(glyphs) = glyphs {
+                let score_transform = c.transform.trans(
+                    args.window_size[0] / 2.0 - 110.0,
+                    args.window_size[1] / 2.0 - 10.0,
+                );
+                Text::new_color(text_color, 20)
+                    .draw(&score_text, glyphs, &c.draw_state, score_transform, gl)
+                    .ok();
+
+                let prompt_transform = c.transform.trans(
+                    args.window_size[0] / 2.0 - 110.0,
+                    args.window_size[1] / 2.0 + 20.0,
+                );
+                Text::new_color(text_color, 16)
+                    .draw(prompt_text, glyphs, &c.draw_state, prompt_transform, gl)
+                    .ok();
+            }
+        });
+    }
+
+    fn update(&mut self, args: &UpdateArgs) {
+        if self.state != GameState::Running {
+            return;
+        }
+
+        self.accumulator += args.dt;
+        let threshold = 1.0 / self.current_ups();
+        while self.accumulator >= threshold {
+            self.accumulator -= threshold;
+            self.tick();
+            if self.state != GameState::Running {
+                break;
+            }
+        }
+    }
+
+    fn tick(&mut self) {
+        if self.autopilot {
+            if let Some(direction) = self.plan(self.size) {
+                self.pending_direction = Some(direction);
+            }
+        }
+
+        self.snake
+            .update(&self.food, self.pending_direction, self.size, self.wrap);
 
         if self.snake.is_eating(&self.food) {
             self.generate_food();
@@ -57,10 +232,126 @@ impl Game {
 
         self.pending_direction = None;
 
-        !self.is_loosing()
+        if self.state == GameState::Running && self.is_loosing() {
+            self.state = GameState::GameOver;
+        }
+    }
+
+    fn current_ups(&self) -> f64 {
+        let bonus = (self.score / FOOD_PER_SPEED_BUMP) as f64;
+        (self.speed + bonus).min(MAX_SPEED)
+    }
+
+    fn plan(&self, size: (u8, u8)) -> Option<Direction> {
+        let width = size.0 as i32;
+        let height = size.1 as i32;
+        let wrap = self.wrap;
+        let start = *self.snake.head();
+        let goal = self.food.position;
+
+        let mut blocked: HashSet<(i32, i32)> = self.snake.body.iter().cloned().collect();
+        if let Some(&tail) = self.snake.body.back() {
+            blocked.remove(&tail);
+        }
+        blocked.extend(self.walls.iter().cloned());
+
+        let in_bounds = |(x, y): (i32, i32)| x >= 0 && x < width && y >= 0 && y < height;
+        let heuristic = |pos: (i32, i32)| (pos.0 - goal.0).abs() + (pos.1 - goal.1).abs();
+        let step = |from: (i32, i32), direction: Direction| {
+            let (x, y) = match direction {
+                Direction::Left => (from.0 - 1, from.1),
+                Direction::Right => (from.0 + 1, from.1),
+                Direction::Up => (from.0, from.1 - 1),
+                Direction::Down => (from.0, from.1 + 1),
+            };
+            if wrap {
+                (
+                    ((x % width) + width) % width,
+                    ((y % height) + height) % height,
+                )
+            } else {
+                (x, y)
+            }
+        };
+        let is_reachable = |pos: (i32, i32)| (wrap || in_bounds(pos)) && !blocked.contains(&pos);
+        let guard_neck = |direction: Direction| {
+            if direction == self.snake.direction.opposite() {
+                self.snake.direction
+            } else {
+                direction
+            }
+        };
+
+        let mut open = BinaryHeap::new();
+        open.push(Reverse((heuristic(start), start)));
+        let mut first_step: HashMap<(i32, i32), Direction> = HashMap::new();
+        let mut best_g: HashMap<(i32, i32), i32> = HashMap::new();
+        best_g.insert(start, 0);
+
+        while let Some(Reverse((_, current))) = open.pop() {
+            if current == goal {
+                return first_step.get(&current).copied().map(guard_neck);
+            }
+
+            for &direction in &[
+                Direction::Right,
+                Direction::Left,
+                Direction::Down,
+                Direction::Up,
+            ] {
+                let next = step(current, direction);
+                if !is_reachable(next) {
+                    continue;
+                }
+                let tentative_g = best_g[&current] + 1;
+                if tentative_g < *best_g.get(&next).unwrap_or(&i32::MAX) {
+                    best_g.insert(next, tentative_g);
+                    let leading_direction = if current == start {
+                        direction
+                    } else {
+                        first_step[&current]
+                    };
+                    first_step.insert(next, leading_direction);
+                    open.push(Reverse((tentative_g + heuristic(next), next)));
+                }
+            }
+        }
+
+        [
+            Direction::Up,
+            Direction::Down,
+            Direction::Left,
+            Direction::Right,
+        ]
+        .iter()
+        .cloned()
+        .find(|&direction| {
+            direction != self.snake.direction.opposite() && is_reachable(step(start, direction))
+        })
     }
 
     fn pressed(&mut self, button: &Button) {
+        if self.state != GameState::Running {
+            if let &Button::Keyboard(Key::Return) = button {
+                self.reset();
+            }
+            return;
+        }
+
+        if let &Button::Keyboard(Key::A) = button {
+            self.autopilot = !self.autopilot;
+            return;
+        }
+
+        if let &Button::Keyboard(Key::W) = button {
+            self.wrap = !self.wrap;
+            return;
+        }
+
+        if self.autopilot {
+            return;
+        }
+
         if let Some(_) = self.pending_direction {
             return;
         }
@@ -81,14 +372,46 @@ impl Game {
         }
     }
 
+    fn reset(&mut self) {
+        self.snake = Snake::new();
+        self.score = 0;
+        self.pending_direction = None;
+        self.state = GameState::Running;
+        self.accumulator = 0.0;
+        self.generate_food();
+    }
+
     fn generate_food(&mut self) {
         let (width, height) = self.size;
-        let index = rand::thread_rng().gen_range(0, width as i32 * height as i32);
-        self.food.position = (index % width as i32, index / width as i32);
+        let total_cells = width as usize * height as usize;
+        let mut occupied: HashSet<(i32, i32)> = self.snake.body.iter().cloned().collect();
+        occupied.extend(self.walls.iter().cloned());
+
+        if occupied.len() >= total_cells {
+            self.state = GameState::Won;
+            return;
+        }
+
+        let mut free_pick = rand::thread_rng().gen_range(0, total_cells - occupied.len());
+
+        for y in 0..height as i32 {
+            for x in 0..width as i32 {
+                if occupied.contains(&(x, y)) {
+                    continue;
+                }
+                if free_pick == 0 {
+                    self.food.position = (x, y);
+                    return;
+                }
+                free_pick -= 1;
+            }
+        }
     }
 
     fn is_loosing(&self) -> bool {
-        self.snake.is_eating_itself() || self.snake.is_out_of_bounds(self.size)
+        self.snake.is_eating_itself()
+            || (!self.wrap && self.snake.is_out_of_bounds(self.size))
+            || self.walls.contains(self.snake.head())
     }
 }
 
@@ -100,6 +423,17 @@ enum Direction {
     Down,
 }
 
+impl Direction {
+    fn opposite(self) -> Direction {
+        match self {
+            Direction::Right => Direction::Left,
+            Direction::Left => Direction::Right,
+            Direction::Up => Direction::Down,
+            Direction::Down => Direction::Up,
+        }
+    }
+}
+
 struct Snake {
     body: LinkedList<(i32, i32)>,
     direction: Direction,
@@ -120,7 +454,13 @@ impl Snake {
         self.body.front().expect("The snake has no body")
     }
 
-    fn update(&mut self, food: &Food, new_direction: Option<Direction>) {
+    fn update(
+        &mut self,
+        food: &Food,
+        new_direction: Option<Direction>,
+        size: (u8, u8),
+        wrap: bool,
+    ) {
         if let Some(direction) = new_direction {
             self.direction = direction;
         }
@@ -132,6 +472,11 @@ impl Snake {
             Direction::Up => new_head.1 -= 1,
             Direction::Down => new_head.1 += 1,
         }
+        if wrap {
+            let (width, height) = (size.0 as i32, size.1 as i32);
+            new_head.0 = (new_head.0 % width + width) % width;
+            new_head.1 = (new_head.1 % height + height) % height;
+        }
         self.body.push_front(new_head);
         if !self.is_eating(food) {
             self.body.pop_back();
@@ -164,20 +509,19 @@ struct Food {
 }
 
 trait Renderable {
-    fn render(&self, gl: &mut GlGraphics, args: &RenderArgs);
+    fn render(&self, gl: &mut GlGraphics, args: &RenderArgs, cell_size: f64, color: [f32; 4]);
 }
 
 impl Renderable for Snake {
-    fn render(&self, gl: &mut GlGraphics, args: &RenderArgs) {
-        fn eyes() -> ([f64; 4], [f64; 4]) {
+    fn render(&self, gl: &mut GlGraphics, args: &RenderArgs, cell_size: f64, color: [f32; 4]) {
+        fn eyes(cell_size: f64) -> ([f64; 4], [f64; 4]) {
             let left =
-                graphics::rectangle::square(-0.35 * GRID_STEP, -0.3 * GRID_STEP, GRID_STEP * 0.2);
+                graphics::rectangle::square(-0.35 * cell_size, -0.3 * cell_size, cell_size * 0.2);
             let right =
-                graphics::rectangle::square(0.15 * GRID_STEP, -0.3 * GRID_STEP, GRID_STEP * 0.2);
+                graphics::rectangle::square(0.15 * cell_size, -0.3 * cell_size, cell_size * 0.2);
             (left, right)
         }
 
-        let red: [f32; 4] = [0.99, 0.16, 0.03, 1.0];
         let eye_color: [f32; 4] = [0.04, 0.05, 0.06, 1.0];
 
         let squares: Vec<graphics::types::Rectangle> = self
@@ -185,9 +529,9 @@ impl Renderable for Snake {
             .iter()
             .map(|&(x, y)| {
                 graphics::rectangle::square(
-                    x as f64 * GRID_STEP + 0.5,
-                    y as f64 * GRID_STEP + 0.5,
-                    GRID_STEP - 1.0,
+                    x as f64 * cell_size + 0.5,
+                    y as f64 * cell_size + 0.5,
+                    cell_size - 1.0,
                 )
             })
             .collect();
@@ -197,14 +541,14 @@ impl Renderable for Snake {
             let transform = c.transform;
 
             for square in squares {
-                graphics::rectangle(red, square, transform, gl);
+                graphics::rectangle(color, square, transform, gl);
             }
 
             let eye_transform = c
                 .transform
                 .trans(
-                    (head.0 as f64 + 0.5) * GRID_STEP + 0.5,
-                    (head.1 as f64 + 0.5) * GRID_STEP + 0.5,
+                    (head.0 as f64 + 0.5) * cell_size + 0.5,
+                    (head.1 as f64 + 0.5) * cell_size + 0.5,
                 )
                 .rot_deg(match self.direction {
                     Direction::Up => 0.0,
@@ -212,7 +556,7 @@ impl Renderable for Snake {
                     Direction::Down => 180.0,
                     Direction::Left => 270.0,
                 });
-            let (left, right) = eyes();
+            let (left, right) = eyes(cell_size);
             graphics::rectangle(eye_color, left, eye_transform, gl);
             graphics::rectangle(eye_color, right, eye_transform, gl);
         })
@@ -220,20 +564,19 @@ impl Renderable for Snake {
 }
 
 impl Renderable for Food {
-    fn render(&self, gl: &mut GlGraphics, args: &RenderArgs) {
-        let blue: [f32; 4] = [0.08, 0.4, 0.53, 1.0];
+    fn render(&self, gl: &mut GlGraphics, args: &RenderArgs, cell_size: f64, color: [f32; 4]) {
         let (x, y) = self.position;
 
         let square = graphics::rectangle::square(
-            x as f64 * GRID_STEP + 0.5,
-            y as f64 * GRID_STEP + 0.5,
-            GRID_STEP - 1.0,
+            x as f64 * cell_size + 0.5,
+            y as f64 * cell_size + 0.5,
+            cell_size - 1.0,
         );
 
         gl.draw(args.viewport(), |c, gl| {
             let transform = c.transform;
 
-            graphics::rectangle(blue, square, transform, gl);
+            graphics::rectangle(color, square, transform, gl);
         })
     }
 }
@@ -255,11 +598,22 @@ impl From<Color> for [f32; 4] {
 fn main() {
     let opengl = OpenGL::V4_5;
 
+    let cli_args: Vec<String> = env::args().skip(1).collect();
+
+    let mut config = match cli_args.iter().find(|arg| arg.parse::<f64>().is_err()) {
+        Some(path) => Config::load(path),
+        None => Config::default(),
+    };
+
+    if let Some(speed) = cli_args.iter().find_map(|arg| arg.parse::<f64>().ok()) {
+        config.speed = speed;
+    }
+
     let mut window: Window = WindowSettings::new(
         "Snake",
         [
-            (GRID_STEP * WIDTH as f64) as u32,
-            (GRID_STEP * HEIGHT as f64) as u32,
+            (config.cell_size * config.width as f64) as u32,
+            (config.cell_size * config.height as f64) as u32,
         ],
     )
     .graphics_api(opengl)
@@ -267,20 +621,33 @@ fn main() {
     .build()
     .unwrap();
 
-    let mut game = Game::new(GlGraphics::new(opengl), (WIDTH, HEIGHT));
+    let glyphs = find_folder::Search::ParentsThenKids(3, 3)
+        .for_folder("assets")
+        .ok()
+        .and_then(|assets| {
+            GlyphCache::new(
+                assets.join("FiraSans-Regular.ttf"),
+                (),
+                TextureSettings::new(),
+            )
+            .ok()
+        });
+    if glyphs.is_none() {
+        eprintln!("Could not load the game-over font, continuing without on-screen text");
+    }
+
+    let mut game = Game::new(GlGraphics::new(opengl), glyphs, &config);
 
     let mut settings = EventSettings::new();
-    settings.ups = 8;
+    settings.ups = 60;
     let mut events = Events::new(settings);
     while let Some(e) = events.next(&mut window) {
         if let Some(args) = e.render_args() {
             game.render(&args);
         }
 
-        if let Some(_args) = e.update_args() {
-            if !game.update() {
-                break;
-            }
+        if let Some(args) = e.update_args() {
+            game.update(&args);
         }
 
         if let Some(args) = e.button_args() {
@@ -288,5 +655,8 @@ fn main() {
         }
     }
 
-    println!("You died after eating {} food stuff!", game.score);
+    match game.state {
+        GameState::Won => println!("You won after eating {} food stuff!", game.score),
+        _ => println!("You died after eating {} food stuff!", game.score),
+    }
 }